@@ -0,0 +1,147 @@
+//! Runtime perfect-hash map construction, without code generation.
+//!
+//! `Map::from_entries` builds a `phf_generator`-backed perfect hash map from
+//! entries that are only known at runtime, storing `disps`/`entries` in
+//! owned `Vec`s instead of the `&'static` slices `phf_codegen` emits for a
+//! build-time key set.
+extern crate phf_generator;
+extern crate phf_shared;
+
+use phf_shared::PhfHash;
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// A perfect hash map, built from a known set of entries at runtime.
+///
+/// Lookups behave identically to `phf::Map`, which is built the same way
+/// but from Rust source generated ahead of time by `phf_codegen`.
+pub struct Map<K, V> {
+    key: u64,
+    disps: Vec<(u32, u32)>,
+    entries: Vec<(K, V)>,
+}
+
+impl<K: PhfHash + Hash + Eq, V> Map<K, V> {
+    /// Builds a perfect hash map from the given entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are any duplicate keys.
+    pub fn from_entries<I>(entries: I) -> Map<K, V>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let entries: Vec<(K, V)> = entries.into_iter().collect();
+
+        let mut set = HashSet::new();
+        for &(ref key, _) in &entries {
+            if !set.insert(key) {
+                panic!("duplicate key");
+            }
+        }
+
+        let keys: Vec<_> = entries.iter().map(|&(ref k, _)| k).collect();
+        let state = phf_generator::generate_hash(&keys);
+
+        // `state.map` is a permutation of `0..entries.len()` that gives, for
+        // each hash table slot, the index of the entry that belongs there.
+        let mut entries: Vec<_> = entries.into_iter().map(Some).collect();
+        let entries = state
+            .map
+            .iter()
+            .map(|&idx| entries[idx].take().expect("each index appears once"))
+            .collect();
+
+        Map {
+            key: state.key,
+            disps: state.disps,
+            entries,
+        }
+    }
+
+    /// Returns a reference to the value that `key` maps to.
+    pub fn get<T: ?Sized>(&self, key: &T) -> Option<&V>
+    where
+        T: Eq + PhfHash,
+        K: Borrow<T>,
+    {
+        self.get_entry(key).map(|(_, v)| v)
+    }
+
+    /// Returns a reference to the key/value pair that `key` maps to.
+    pub fn get_entry<T: ?Sized>(&self, key: &T) -> Option<(&K, &V)>
+    where
+        T: Eq + PhfHash,
+        K: Borrow<T>,
+    {
+        if self.disps.is_empty() {
+            return None;
+        }
+
+        let hashes = phf_shared::hash(key, &self.key);
+        let index = phf_shared::get_index(&hashes, &self.disps, self.entries.len()) as usize;
+        let (ref k, ref v) = self.entries[index];
+        if key == k.borrow() {
+            Some((k, v))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Map;
+
+    #[test]
+    fn get_present_and_absent() {
+        let map = Map::from_entries(vec![
+            ("a".to_string(), 1),
+            ("b".to_string(), 2),
+            ("c".to_string(), 3),
+            ("d".to_string(), 4),
+            ("e".to_string(), 5),
+        ]);
+
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map.get("e"), Some(&5));
+        assert_eq!(map.get("z"), None);
+    }
+
+    #[test]
+    fn get_entry_present_and_absent() {
+        let map = Map::from_entries(vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+
+        let (k, v) = map.get_entry("a").expect("key `a` should be present");
+        assert_eq!(k, "a");
+        assert_eq!(*v, 1);
+
+        assert!(map.get_entry("z").is_none());
+    }
+
+    #[test]
+    fn empty_map() {
+        let map: Map<String, i32> = Map::from_entries(Vec::new());
+
+        assert_eq!(map.len(), 0);
+        assert!(map.is_empty());
+        assert_eq!(map.get("anything"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate key")]
+    fn duplicate_keys_panic() {
+        Map::from_entries(vec![("a".to_string(), 1), ("a".to_string(), 2)]);
+    }
+}
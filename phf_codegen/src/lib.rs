@@ -56,6 +56,20 @@
 //! }
 //! ```
 //!
+//! # Other builders
+//!
+//! `OrderedMap`/`OrderedSet` work the same way as `Map`/`Set`, but also emit
+//! an `idxs` table so the resulting `phf::OrderedMap`/`phf::OrderedSet`
+//! iterates in the order entries were added.
+//!
+//! All four builders support:
+//!
+//! * `.seed(key)`, for byte-for-byte reproducible output across builds.
+//! * `.stats(true)` (or the `PHF_STATS` environment variable), to report
+//!   hash generation diagnostics to stderr.
+//! * `build_to_string()`, an alternative to `build` for callers -- such as
+//!   procedural macros -- that want a `String` rather than a writer.
+//!
 //! # Note
 //!
 //! The compiler's stack will overflow when processing extremely long method
@@ -81,13 +95,17 @@
 #![doc(html_root_url = "https://docs.rs/phf_codegen/0.7.20")]
 extern crate phf_generator;
 extern crate phf_shared;
+extern crate rand;
 
+use rand::{Rng, SeedableRng, XorShiftRng};
 use std::ascii;
 use std::collections::HashSet;
+use std::env;
 use std::fmt::{self, Write as FmtWrite};
 use std::hash::Hash;
 use std::io;
 use std::io::prelude::*;
+use std::time::Instant;
 
 pub trait Source {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result;
@@ -137,11 +155,82 @@ where
     }
 }
 
+/// Adapts an `io::Write` sink so the `fmt::Write`-based generation code can
+/// stream straight into it, rather than buffering the whole output first.
+///
+/// `fmt::Write` methods can't return `io::Error`, so any error from the
+/// underlying sink is stashed here and `build` recovers it once formatting
+/// reports failure.
+struct IoFmtAdapter<'a, W: 'a> {
+    inner: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: Write> IoFmtAdapter<'a, W> {
+    fn new(inner: &'a mut W) -> IoFmtAdapter<'a, W> {
+        IoFmtAdapter { inner, error: None }
+    }
+
+    fn take_error(&mut self) -> io::Error {
+        self.error
+            .take()
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "formatting error"))
+    }
+}
+
+impl<'a, W: Write> FmtWrite for IoFmtAdapter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.inner.write_all(s.as_bytes()) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.error = Some(e);
+                Err(fmt::Error)
+            }
+        }
+    }
+}
+
+/// Retries CHD construction until a bucket assignment succeeds, returning
+/// the resulting state and how many attempts it took.
+fn try_until_generated<K: AsRef<[u8]> + Hash + Eq, R: Rng>(
+    keys: &[K],
+    rng: &mut R,
+) -> (phf_generator::HashState, usize) {
+    let mut attempts = 0;
+    let state = loop {
+        attempts += 1;
+        if let Some(state) = phf_generator::try_generate_hash(keys, rng) {
+            break state;
+        }
+    };
+    (state, attempts)
+}
+
+/// Approximates the largest number of keys assigned to a single
+/// displacement bucket, for `PHF_STATS` reporting.
+fn max_bucket_size<K: AsRef<[u8]> + Hash>(keys: &[K], hash_key: u64) -> usize {
+    const LAMBDA: usize = 5;
+
+    if keys.is_empty() {
+        return 0;
+    }
+
+    let buckets_len = (keys.len() + LAMBDA - 1) / LAMBDA;
+    let mut sizes = vec![0usize; buckets_len];
+    for key in keys {
+        let hashes = phf_shared::hash(key, &hash_key);
+        sizes[(hashes.g % buckets_len as u32) as usize] += 1;
+    }
+    sizes.into_iter().max().unwrap_or(0)
+}
+
 /// A builder for the `phf::Map` type.
 pub struct Map<K> {
     keys: Vec<K>,
     values: Vec<String>,
     path: String,
+    seed: Option<[u8; 16]>,
+    stats: bool,
 }
 
 impl<K: AsRef<[u8]> + Hash + Eq + Source> Map<K> {
@@ -151,6 +240,8 @@ impl<K: AsRef<[u8]> + Hash + Eq + Source> Map<K> {
             keys: vec![],
             values: vec![],
             path: "::phf".to_string(),
+            seed: None,
+            stats: false,
         }
     }
 
@@ -160,6 +251,29 @@ impl<K: AsRef<[u8]> + Hash + Eq + Source> Map<K> {
         self
     }
 
+    /// Seeds hash generation with a fixed 16-byte seed instead of letting
+    /// `phf_generator` pick a random one.
+    ///
+    /// This makes `build`'s output deterministic: the same entries with the
+    /// same seed always produce byte-for-byte identical generated source,
+    /// which matters for reproducible builds and for diffing checked-in
+    /// generated files.
+    pub fn seed(&mut self, seed: [u8; 16]) -> &mut Map<K> {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Enables reporting of hash generation statistics to stderr.
+    ///
+    /// This can also be turned on for a single build without touching the
+    /// build script by setting the `PHF_STATS` environment variable. Useful
+    /// for finding out whether `generate_hash` is the bottleneck in a build
+    /// script that is code-generating a very large table.
+    pub fn stats(&mut self, stats: bool) -> &mut Map<K> {
+        self.stats = stats;
+        self
+    }
+
     /// Adds an entry to the builder.
     ///
     /// `value` will be written exactly as provided in the constructed source.
@@ -175,6 +289,36 @@ impl<K: AsRef<[u8]> + Hash + Eq + Source> Map<K> {
     ///
     /// Panics if there are any duplicate keys.
     pub fn build<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut writer = IoFmtAdapter::new(w);
+        self.write_to(&mut writer).map_err(|_| writer.take_error())
+    }
+
+    /// Constructs a `phf::Map`, returning the generated Rust source as a
+    /// `String` rather than writing it to an `io::Write`.
+    ///
+    /// This is handy when generating code from a procedural macro, where a
+    /// `String` (or token stream) is wanted rather than a file handle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are any duplicate keys.
+    pub fn build_to_string(&self) -> String {
+        let mut buf = String::new();
+        self.write_to(&mut buf).expect("formatting to a `String` cannot fail");
+        buf
+    }
+
+    /// Runs CHD construction, retrying with a fresh seed whenever a
+    /// bucket's displacement search fails, and returns the resulting state
+    /// along with how many attempts it took.
+    fn generate_hash(&self) -> (phf_generator::HashState, usize) {
+        match self.seed {
+            Some(seed) => try_until_generated(&self.keys, &mut XorShiftRng::from_seed(seed)),
+            None => try_until_generated(&self.keys, &mut rand::thread_rng()),
+        }
+    }
+
+    fn write_to<W: FmtWrite>(&self, w: &mut W) -> fmt::Result {
         let mut set = HashSet::new();
         for key in &self.keys {
             if !set.insert(key) {
@@ -182,7 +326,21 @@ impl<K: AsRef<[u8]> + Hash + Eq + Source> Map<K> {
             }
         }
 
-        let state = phf_generator::generate_hash(&self.keys);
+        let print_stats = self.stats || env::var_os("PHF_STATS").is_some();
+        let start = if print_stats { Some(Instant::now()) } else { None };
+
+        let (state, attempts) = self.generate_hash();
+
+        if let Some(start) = start {
+            eprintln!(
+                "PHF_STATS: {} entries, {} displacement buckets, max bucket size {}, {} attempt(s), {:?} in generate_hash",
+                self.keys.len(),
+                state.disps.len(),
+                max_bucket_size(&self.keys, state.key),
+                attempts,
+                start.elapsed()
+            );
+        }
 
         try!(write!(
             w,
@@ -240,6 +398,20 @@ impl<T: AsRef<[u8]> + Hash + Eq + Source> Set<T> {
         self
     }
 
+    /// Seeds hash generation with a fixed 16-byte seed instead of letting
+    /// `phf_generator` pick a random one. See `Map::seed`.
+    pub fn seed(&mut self, seed: [u8; 16]) -> &mut Set<T> {
+        self.map.seed(seed);
+        self
+    }
+
+    /// Enables reporting of hash generation statistics to stderr. See
+    /// `Map::stats`.
+    pub fn stats(&mut self, stats: bool) -> &mut Set<T> {
+        self.map.stats(stats);
+        self
+    }
+
     /// Adds an entry to the builder.
     pub fn entry(&mut self, entry: T) -> &mut Set<T> {
         self.map.entry(entry, "()");
@@ -252,8 +424,275 @@ impl<T: AsRef<[u8]> + Hash + Eq + Source> Set<T> {
     ///
     /// Panics if there are any duplicate entries.
     pub fn build<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut writer = IoFmtAdapter::new(w);
+        self.write_to(&mut writer).map_err(|_| writer.take_error())
+    }
+
+    /// Constructs a `phf::Set`, returning the generated Rust source as a
+    /// `String` rather than writing it to an `io::Write`. See
+    /// `Map::build_to_string`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are any duplicate entries.
+    pub fn build_to_string(&self) -> String {
+        let mut buf = String::new();
+        self.write_to(&mut buf).expect("formatting to a `String` cannot fail");
+        buf
+    }
+
+    fn write_to<W: FmtWrite>(&self, w: &mut W) -> fmt::Result {
         try!(write!(w, "{}::Set {{ map: ", self.map.path));
-        try!(self.map.build(w));
+        try!(self.map.write_to(w));
+        write!(w, " }}")
+    }
+}
+
+/// A builder for the `phf::OrderedMap` type.
+pub struct OrderedMap<K> {
+    keys: Vec<K>,
+    values: Vec<String>,
+    path: String,
+    seed: Option<[u8; 16]>,
+    stats: bool,
+}
+
+impl<K: AsRef<[u8]> + Hash + Eq + Source> OrderedMap<K> {
+    /// Creates a new `phf::OrderedMap` builder.
+    pub fn new() -> OrderedMap<K> {
+        OrderedMap {
+            keys: vec![],
+            values: vec![],
+            path: "::phf".to_string(),
+            seed: None,
+            stats: false,
+        }
+    }
+
+    /// Set the path to the `phf` crate from the global namespace
+    pub fn phf_path(&mut self, path: &str) -> &mut OrderedMap<K> {
+        self.path = path.to_owned();
+        self
+    }
+
+    /// Seeds hash generation with a fixed 16-byte seed instead of letting
+    /// `phf_generator` pick a random one. See `Map::seed`.
+    pub fn seed(&mut self, seed: [u8; 16]) -> &mut OrderedMap<K> {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Enables reporting of hash generation statistics to stderr. See
+    /// `Map::stats`.
+    pub fn stats(&mut self, stats: bool) -> &mut OrderedMap<K> {
+        self.stats = stats;
+        self
+    }
+
+    /// Adds an entry to the builder.
+    ///
+    /// `value` will be written exactly as provided in the constructed source.
+    /// Entries are kept in the order they were added so that the emitted
+    /// `phf::OrderedMap` iterates in that same order at runtime.
+    pub fn entry(&mut self, key: K, value: &str) -> &mut OrderedMap<K> {
+        self.keys.push(key);
+        self.values.push(value.to_owned());
+        self
+    }
+
+    /// Runs CHD construction, honoring `seed` if one was set.
+    fn generate_hash(&self) -> (phf_generator::HashState, usize) {
+        match self.seed {
+            Some(seed) => try_until_generated(&self.keys, &mut XorShiftRng::from_seed(seed)),
+            None => try_until_generated(&self.keys, &mut rand::thread_rng()),
+        }
+    }
+
+    /// Constructs a `phf::OrderedMap`, outputting Rust source to the provided
+    /// writer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are any duplicate keys.
+    pub fn build<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut writer = IoFmtAdapter::new(w);
+        self.write_to(&mut writer).map_err(|_| writer.take_error())
+    }
+
+    /// Constructs a `phf::OrderedMap`, returning the generated Rust source as
+    /// a `String` rather than writing it to an `io::Write`. See
+    /// `Map::build_to_string`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are any duplicate keys.
+    pub fn build_to_string(&self) -> String {
+        let mut buf = String::new();
+        self.write_to(&mut buf).expect("formatting to a `String` cannot fail");
+        buf
+    }
+
+    fn write_to<W: FmtWrite>(&self, w: &mut W) -> fmt::Result {
+        let mut set = HashSet::new();
+        for key in &self.keys {
+            if !set.insert(key) {
+                panic!("duplicate key `{}`", Displayify(key));
+            }
+        }
+
+        let print_stats = self.stats || env::var_os("PHF_STATS").is_some();
+        let start = if print_stats { Some(Instant::now()) } else { None };
+
+        let (state, attempts) = self.generate_hash();
+
+        if let Some(start) = start {
+            eprintln!(
+                "PHF_STATS: {} entries, {} displacement buckets, max bucket size {}, {} attempt(s), {:?} in generate_hash",
+                self.keys.len(),
+                state.disps.len(),
+                max_bucket_size(&self.keys, state.key),
+                attempts,
+                start.elapsed()
+            );
+        }
+
+        try!(write!(
+            w,
+            "{}::OrderedMap {{
+    key: {},
+    disps: &[",
+            self.path, state.key
+        ));
+        for &(d1, d2) in &state.disps {
+            try!(write!(
+                w,
+                "
+        ({}, {}),",
+                d1, d2
+            ));
+        }
+        try!(write!(
+            w,
+            "
+    ],
+    idxs: &[",
+        ));
+        for &idx in &state.map {
+            try!(write!(
+                w,
+                "
+        {},",
+                idx
+            ));
+        }
+        try!(write!(
+            w,
+            "
+    ],
+    entries: &[",
+        ));
+        for i in 0..self.keys.len() {
+            try!(write!(
+                w,
+                "
+        ({}, {}),",
+                Displayify(&self.keys[i]),
+                &self.values[i]
+            ));
+        }
+        write!(
+            w,
+            "
+    ],
+}}"
+        )
+    }
+}
+
+/// A builder for the `phf::OrderedSet` type.
+pub struct OrderedSet<T> {
+    map: OrderedMap<T>,
+}
+
+impl<T: AsRef<[u8]> + Hash + Eq + Source> OrderedSet<T> {
+    /// Constructs a new `phf::OrderedSet` builder.
+    pub fn new() -> OrderedSet<T> {
+        OrderedSet {
+            map: OrderedMap::new(),
+        }
+    }
+
+    /// Set the path to the `phf` crate from the global namespace
+    pub fn phf_path(&mut self, path: &str) -> &mut OrderedSet<T> {
+        self.map.phf_path(path);
+        self
+    }
+
+    /// Seeds hash generation with a fixed 16-byte seed instead of letting
+    /// `phf_generator` pick a random one. See `Map::seed`.
+    pub fn seed(&mut self, seed: [u8; 16]) -> &mut OrderedSet<T> {
+        self.map.seed(seed);
+        self
+    }
+
+    /// Enables reporting of hash generation statistics to stderr. See
+    /// `Map::stats`.
+    pub fn stats(&mut self, stats: bool) -> &mut OrderedSet<T> {
+        self.map.stats(stats);
+        self
+    }
+
+    /// Adds an entry to the builder.
+    pub fn entry(&mut self, entry: T) -> &mut OrderedSet<T> {
+        self.map.entry(entry, "()");
+        self
+    }
+
+    /// Constructs a `phf::OrderedSet`, outputting Rust source to the provided
+    /// writer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are any duplicate entries.
+    pub fn build<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut writer = IoFmtAdapter::new(w);
+        self.write_to(&mut writer).map_err(|_| writer.take_error())
+    }
+
+    /// Constructs a `phf::OrderedSet`, returning the generated Rust source as
+    /// a `String` rather than writing it to an `io::Write`. See
+    /// `Map::build_to_string`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are any duplicate entries.
+    pub fn build_to_string(&self) -> String {
+        let mut buf = String::new();
+        self.write_to(&mut buf).expect("formatting to a `String` cannot fail");
+        buf
+    }
+
+    fn write_to<W: FmtWrite>(&self, w: &mut W) -> fmt::Result {
+        try!(write!(w, "{}::OrderedSet {{ map: ", self.map.path));
+        try!(self.map.write_to(w));
         write!(w, " }}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Map;
+
+    #[test]
+    fn seed_produces_identical_output() {
+        let build = || {
+            Map::new()
+                .seed([0; 16])
+                .entry("a", "1")
+                .entry("b", "2")
+                .build_to_string()
+        };
+
+        assert_eq!(build(), build());
+    }
+}